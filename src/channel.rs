@@ -1,11 +1,15 @@
 use windows::{
-    core::{Error as WindowsError, Result as WindowsResult},
+    core::Error as WindowsError,
     Win32::{
         Foundation::HANDLE,
-        System::Memory::{MapViewOfFile, UnmapViewOfFile, FILE_MAP_ALL_ACCESS},
+        System::Memory::{
+            MapViewOfFile, UnmapViewOfFile, FILE_MAP_ALL_ACCESS, MEMORY_MAPPED_VIEW_ADDRESS,
+        },
     },
 };
 
+use crate::handle::OwnedHandle;
+
 pub(crate) const CHANNEL_SIZE: u32 = 1 << 20;
 
 pub(crate) enum Error {
@@ -16,22 +20,24 @@ pub(crate) type Result<T> = std::result::Result<T, Error>;
 
 pub(crate) struct ChannelArgs {
     pub exe: String,
-    pub file: HANDLE,
-    pub mutex: HANDLE,
-    pub wait_event: HANDLE,
-    pub signal_event: HANDLE,
+    pub file: OwnedHandle,
+    pub mutex: OwnedHandle,
+    pub wait_event: OwnedHandle,
+    pub signal_event: OwnedHandle,
 }
 
 impl ChannelArgs {
     /// Builds the command line the child parses back into its inherited
-    /// handles: the exe path followed by each handle's raw value.
+    /// handles: the exe path followed by each handle's raw value, each
+    /// quoted MSVCRT-style so the child's `CommandLineToArgvW` agrees with
+    /// what we intended.
     pub(crate) fn to_cmd_line(&self) -> String {
         let tokens = [
             self.exe.clone(),
-            self.file.0.to_string(),
-            self.mutex.0.to_string(),
-            self.wait_event.0.to_string(),
-            self.signal_event.0.to_string(),
+            self.file.as_raw().0.to_string(),
+            self.mutex.as_raw().0.to_string(),
+            self.wait_event.as_raw().0.to_string(),
+            self.signal_event.as_raw().0.to_string(),
         ];
 
         tokens
@@ -42,34 +48,77 @@ impl ChannelArgs {
     }
 }
 
-/// Wraps an argument in double quotes if it contains a space or tab.
+/// Quotes a single command-line argument using the same escaping rules as
+/// the MSVCRT/CRT argv parser (and `CommandLineToArgvW`): wrap in double
+/// quotes when the argument is empty or contains a space, tab, or quote;
+/// within the quoted span, double every run of backslashes that precedes a
+/// quote (one extra to escape it) or the closing quote, leaving backslashes
+/// elsewhere untouched.
 fn quote_arg(arg: &str) -> String {
-    if arg.contains([' ', '\t']) {
-        format!("\"{arg}\"")
-    } else {
-        arg.to_string()
+    let needs_quoting = arg.is_empty() || arg.contains([' ', '\t', '"']);
+    if !needs_quoting {
+        return arg.to_string();
+    }
+
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+
+    let mut backslashes = 0usize;
+    for c in arg.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+            }
+            '"' => {
+                quoted.extend(std::iter::repeat('\\').take(backslashes * 2 + 1));
+                quoted.push('"');
+                backslashes = 0;
+            }
+            _ => {
+                quoted.extend(std::iter::repeat('\\').take(backslashes));
+                quoted.push(c);
+                backslashes = 0;
+            }
+        }
     }
+
+    // Trailing backslashes must be doubled since they now precede the closing quote.
+    quoted.extend(std::iter::repeat('\\').take(backslashes * 2));
+    quoted.push('"');
+
+    quoted
 }
 
 pub(crate) struct Channel {
-    file: HANDLE,
-    view: *mut std::ffi::c_void,
-    mutex: HANDLE,
-    wait_event: HANDLE,
-    signal_event: HANDLE,
+    file: OwnedHandle,
+    view: MEMORY_MAPPED_VIEW_ADDRESS,
+    mutex: OwnedHandle,
+    wait_event: OwnedHandle,
+    signal_event: OwnedHandle,
 }
 
 impl Channel {
+    pub(crate) fn wait_event(&self) -> HANDLE {
+        self.wait_event.as_raw()
+    }
+
     pub(crate) fn create(args: ChannelArgs) -> Result<Channel> {
-        let view =
-            unsafe { MapViewOfFile(args.file, FILE_MAP_ALL_ACCESS, 0, 0, CHANNEL_SIZE as usize) };
+        let view = unsafe {
+            MapViewOfFile(
+                args.file.as_raw(),
+                FILE_MAP_ALL_ACCESS,
+                0,
+                0,
+                CHANNEL_SIZE as usize,
+            )
+        };
         if view.Value.is_null() {
             return Err(Error::Windows(WindowsError::from_win32()));
         }
 
         Ok(Channel {
             file: args.file,
-            view: view.Value,
+            view,
             mutex: args.mutex,
             wait_event: args.wait_event,
             signal_event: args.signal_event,
@@ -80,9 +129,57 @@ impl Channel {
 impl Drop for Channel {
     fn drop(&mut self) {
         unsafe {
-            let _ = UnmapViewOfFile(windows::Win32::System::Memory::MEMORY_MAPPED_VIEW_ADDRESS {
-                Value: self.view,
-            });
+            let _ = UnmapViewOfFile(self.view);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quote_arg;
+
+    #[test]
+    fn quote_arg_cases() {
+        let cases: Vec<(String, String)> = vec![
+            ("".to_string(), "\"\"".to_string()),
+            ("plain".to_string(), "plain".to_string()),
+            ("has space".to_string(), "\"has space\"".to_string()),
+            ("has\ttab".to_string(), "\"has\ttab\"".to_string()),
+            (
+                "has\"quote".to_string(),
+                format!("\"has{}\"quote\"", "\\"),
+            ),
+            (
+                // Backslashes with no following quote are left untouched,
+                // and don't by themselves trigger quoting.
+                r"C:\no\trailing\backslash".to_string(),
+                r"C:\no\trailing\backslash".to_string(),
+            ),
+            (
+                r"trailing\backslash\".to_string(),
+                r"trailing\backslash\".to_string(),
+            ),
+            (
+                // A trailing backslash run is doubled once it ends up
+                // immediately before the closing quote.
+                r"needs space\".to_string(),
+                format!("\"needs space{}\"", "\\".repeat(2)),
+            ),
+            (
+                // No quote/space/tab present, so backslashes pass through
+                // even though there's a run of them.
+                "\\\\".to_string(),
+                "\\\\".to_string(),
+            ),
+            (
+                // N backslashes immediately before a quote become 2N+1.
+                format!("{}\"embedded", "\\".repeat(3)),
+                format!("\"{}\"embedded\"", "\\".repeat(7)),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(quote_arg(&input), expected, "input: {input:?}");
         }
     }
 }
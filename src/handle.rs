@@ -0,0 +1,29 @@
+use windows::Win32::Foundation::{CloseHandle, HANDLE, INVALID_HANDLE_VALUE};
+
+/// An owned kernel handle that closes itself on drop.
+pub(crate) struct OwnedHandle(HANDLE);
+
+impl OwnedHandle {
+    pub(crate) fn as_raw(&self) -> HANDLE {
+        self.0
+    }
+}
+
+impl From<HANDLE> for OwnedHandle {
+    fn from(handle: HANDLE) -> Self {
+        Self(handle)
+    }
+}
+
+impl Drop for OwnedHandle {
+    fn drop(&mut self) {
+        if self.0 != INVALID_HANDLE_VALUE && self.0 != HANDLE::default() {
+            unsafe {
+                let _ = CloseHandle(self.0);
+            }
+        }
+    }
+}
+
+// Process/thread/job/channel handles aren't tied to the thread that created them.
+unsafe impl Send for OwnedHandle {}
@@ -1,18 +1,27 @@
 use std::{
+    collections::HashMap,
     mem::{self, MaybeUninit},
     ptr,
+    time::{Duration, Instant},
 };
 
 use windows::{
-    core::{w, Error as WindowsError, Result as WindowsResult, PWSTR},
+    core::{w, Error as WindowsError, Result as WindowsResult, PCWSTR, PWSTR},
     Win32::{
-        Foundation::{BOOL, HANDLE, INVALID_HANDLE_VALUE},
+        Foundation::{HANDLE, INVALID_HANDLE_VALUE, WAIT_ABANDONED_0, WAIT_OBJECT_0, WAIT_TIMEOUT},
         Security::SECURITY_ATTRIBUTES,
         System::{
+            JobObjects::{
+                AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject,
+                JobObjectExtendedLimitInformation, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+                JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+            },
             Memory::{CreateFileMappingW, PAGE_READWRITE},
             Threading::{
-                CreateEventW, CreateMutexW, CreateProcessW, PROCESS_CREATION_FLAGS,
-                PROCESS_INFORMATION, STARTUPINFOW,
+                CreateEventW, CreateMutexW, CreateProcessW, GetExitCodeProcess,
+                TerminateProcess, WaitForMultipleObjects, WaitForSingleObject,
+                CREATE_UNICODE_ENVIRONMENT, INFINITE, PROCESS_CREATION_FLAGS,
+                PROCESS_INFORMATION, STARTUPINFOW, STILL_ACTIVE,
             },
         },
     },
@@ -21,6 +30,7 @@ use windows::{
 use crate::channel::{
     Channel, ChannelArgs, Error as ChannelError, Result as ChannelResult, CHANNEL_SIZE,
 };
+use crate::handle::OwnedHandle;
 
 pub(crate) enum Error {
     ChannelErr(ChannelError),
@@ -45,17 +55,91 @@ fn result_from_windows<T>(windows_result: WindowsResult<T>) -> Result<T> {
     }
 }
 
+/// Optional overrides for a launched child's working directory and environment.
+#[derive(Default)]
+pub(crate) struct LaunchOptions {
+    cwd: Option<String>,
+    env: Option<HashMap<String, String>>,
+}
+
+impl LaunchOptions {
+    pub fn cwd(mut self, cwd: String) -> Self {
+        self.cwd = Some(cwd);
+        self
+    }
+
+    pub fn env(mut self, env: HashMap<String, String>) -> Self {
+        self.env = Some(env);
+        self
+    }
+}
+
+/// Builds a double-null-terminated `CREATE_UNICODE_ENVIRONMENT` block, with
+/// variables sorted case-insensitively by name as `CreateProcessW` expects.
+fn build_env_block(env: &HashMap<String, String>) -> Vec<u16> {
+    let mut vars: Vec<(&String, &String)> = env.iter().collect();
+    vars.sort_by(|(a, _), (b, _)| a.to_lowercase().cmp(&b.to_lowercase()));
+
+    let mut block = Vec::new();
+    for (key, value) in vars {
+        block.extend(key.encode_utf16());
+        block.push('=' as u16);
+        block.extend(value.encode_utf16());
+        block.push(0);
+    }
+    block.push(0);
+
+    block
+}
+
+// WaitForMultipleObjects refuses more than this many handles in one call.
+const WAIT_GROUP_LIMIT: usize = 64;
+
+// How long each group is polled before moving on to the next, when there's
+// more than one group to round-robin over.
+const WAIT_GROUP_SLICE: Duration = Duration::from_millis(50);
+
 struct Child {
-    info: PROCESS_INFORMATION,
+    process: OwnedHandle,
+    thread: OwnedHandle,
     channel: Channel,
 }
 
 pub(crate) struct Launcher {
+    job: OwnedHandle,
     children: Vec<Child>,
 }
 
 impl Launcher {
-    pub fn launch(&mut self, exe: String) -> Result<()> {
+    pub fn new() -> Result<Self> {
+        // Wrapped immediately so a failure below closes the job object
+        // instead of leaking it.
+        let job: OwnedHandle = result_from_windows(unsafe { CreateJobObjectW(None, None) })?.into();
+
+        let limits = JOBOBJECT_EXTENDED_LIMIT_INFORMATION {
+            BasicLimitInformation: windows::Win32::System::JobObjects::JOBOBJECT_BASIC_LIMIT_INFORMATION {
+                LimitFlags: JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        unsafe {
+            result_from_windows(SetInformationJobObject(
+                job.as_raw(),
+                JobObjectExtendedLimitInformation,
+                &limits as *const _ as *const _,
+                mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+            ))?;
+        }
+
+        Ok(Self {
+            job,
+            children: Vec::new(),
+        })
+    }
+
+    pub fn launch(&mut self, exe: String, options: LaunchOptions) -> Result<()> {
         let args = {
             // The handles are inheritable by child processes
             let handle_attr: SECURITY_ATTRIBUTES = SECURITY_ATTRIBUTES {
@@ -74,56 +158,264 @@ impl Launcher {
                         0,
                         CHANNEL_SIZE,
                         None,
-                    ))?,
-                    mutex: result_from_windows(CreateMutexW(Some(&handle_attr), true, None))?,
+                    ))?
+                    .into(),
+                    mutex: result_from_windows(CreateMutexW(Some(&handle_attr), true, None))?
+                        .into(),
                     wait_event: result_from_windows(CreateEventW(
                         Some(&handle_attr),
                         true,
                         false,
                         None,
-                    ))?,
+                    ))?
+                    .into(),
                     signal_event: result_from_windows(CreateEventW(
                         Some(&handle_attr),
                         true,
                         false,
                         None,
-                    ))?,
+                    ))?
+                    .into(),
                 }
             }
         };
 
+        let env_block = options.env.as_ref().map(build_env_block);
+        let cwd: Vec<u16> = options
+            .cwd
+            .as_ref()
+            .map(|cwd| cwd.encode_utf16().chain(Some(0)).collect())
+            .unwrap_or_default();
+
         let info = unsafe {
             let mut cmd_line: Vec<u16> = args.to_cmd_line().encode_utf16().collect();
             cmd_line.push(0); // null terminator
 
             let mut info = MaybeUninit::<PROCESS_INFORMATION>::uninit();
 
-            if CreateProcessW(
+            let creation_flags = if env_block.is_some() {
+                CREATE_UNICODE_ENVIRONMENT
+            } else {
+                PROCESS_CREATION_FLAGS::default()
+            };
+
+            result_from_windows(CreateProcessW(
                 None,
                 PWSTR(cmd_line.as_mut_ptr()),
                 None,
                 None,
                 true,
-                PROCESS_CREATION_FLAGS::default(),
-                None,
-                None,
+                creation_flags,
+                env_block
+                    .as_ref()
+                    .map(|block| block.as_ptr() as *const _),
+                if options.cwd.is_none() {
+                    PCWSTR::null()
+                } else {
+                    PCWSTR(cwd.as_ptr())
+                },
                 &STARTUPINFOW {
                     cb: mem::size_of::<STARTUPINFOW>() as u32,
                     ..Default::default()
                 },
                 info.as_mut_ptr(),
-            ) == false
+            ))?;
+
+            info.assume_init()
+        };
+
+        // Wrapped immediately so a failure below closes (and kills) the
+        // child instead of leaking it as an untracked orphan.
+        let process: OwnedHandle = info.hProcess.into();
+        let thread: OwnedHandle = info.hThread.into();
+
+        unsafe {
+            if let Err(err) =
+                result_from_windows(AssignProcessToJobObject(self.job.as_raw(), process.as_raw()))
             {
-                return Err(Error::Windows(WindowsError::from_win32()));
+                let _ = TerminateProcess(process.as_raw(), 1);
+                return Err(err);
             }
+        }
 
-            info.assume_init()
+        let channel = match result_from_channel(Channel::create(args)) {
+            Ok(channel) => channel,
+            Err(err) => {
+                unsafe {
+                    let _ = TerminateProcess(process.as_raw(), 1);
+                }
+                return Err(err);
+            }
         };
 
-        let channel = result_from_channel(Channel::create(args))?;
+        self.children.push(Child {
+            process,
+            thread,
+            channel,
+        });
+
+        Ok(())
+    }
+
+    /// Blocks until the child at `index` exits and returns its exit code.
+    pub fn wait(&self, index: usize) -> Result<i32> {
+        let process = self.children[index].process.as_raw();
+
+        unsafe {
+            if WaitForSingleObject(process, INFINITE) != WAIT_OBJECT_0 {
+                return Err(Error::Windows(WindowsError::from_win32()));
+            }
+
+            let mut exit_code = 0u32;
+            result_from_windows(GetExitCodeProcess(process, &mut exit_code))?;
+
+            Ok(exit_code as i32)
+        }
+    }
+
+    /// Polls the child at `index`, returning `None` if it is still running.
+    pub fn try_wait(&self, index: usize) -> Result<Option<i32>> {
+        let process = self.children[index].process.as_raw();
+
+        unsafe {
+            match WaitForSingleObject(process, 0) {
+                WAIT_OBJECT_0 => {}
+                WAIT_TIMEOUT => return Ok(None),
+                _ => return Err(Error::Windows(WindowsError::from_win32())),
+            }
+
+            let mut exit_code = 0u32;
+            result_from_windows(GetExitCodeProcess(process, &mut exit_code))?;
+
+            if exit_code == STILL_ACTIVE.0 as u32 {
+                return Ok(None);
+            }
+
+            Ok(Some(exit_code as i32))
+        }
+    }
 
-        self.children.push(Child { info, channel });
+    /// Forcibly terminates the child at `index`.
+    pub fn kill(&self, index: usize) -> Result<()> {
+        let process = self.children[index].process.as_raw();
+
+        unsafe {
+            result_from_windows(TerminateProcess(process, 1))?;
+        }
 
         Ok(())
     }
+
+    /// Blocks until any child's channel has data ready, returning its index,
+    /// or `None` if `timeout` (milliseconds, or `INFINITE`) elapses first.
+    /// Scales past `WaitForMultipleObjects`'s 64-handle limit by chunking
+    /// the children into groups and round-robining across them.
+    pub fn wait_any(&self, timeout: u32) -> Result<Option<usize>> {
+        if self.children.is_empty() {
+            return Ok(None);
+        }
+
+        let handles: Vec<HANDLE> = self
+            .children
+            .iter()
+            .map(|child| child.channel.wait_event())
+            .collect();
+        let groups: Vec<&[HANDLE]> = handles.chunks(WAIT_GROUP_LIMIT).collect();
+
+        if groups.len() == 1 {
+            return wait_on_group(groups[0], 0, timeout);
+        }
+
+        let deadline = (timeout != INFINITE)
+            .then(|| Instant::now() + Duration::from_millis(timeout as u64));
+
+        loop {
+            // Never wait longer, per group, than what's left of the caller's
+            // timeout, so chunking doesn't make wait_any overrun it.
+            let slice_timeout = match deadline {
+                Some(deadline) => deadline
+                    .saturating_duration_since(Instant::now())
+                    .min(WAIT_GROUP_SLICE)
+                    .as_millis() as u32,
+                None => WAIT_GROUP_SLICE.as_millis() as u32,
+            };
+
+            let mut base = 0;
+            for group in &groups {
+                if let Some(index) = wait_on_group(group, base, slice_timeout)? {
+                    return Ok(Some(index));
+                }
+                base += group.len();
+            }
+
+            if let Some(deadline) = deadline {
+                if Instant::now() >= deadline {
+                    return Ok(None);
+                }
+            }
+        }
+    }
+}
+
+/// Waits on a single `WaitForMultipleObjects`-sized group of handles,
+/// translating a signaled or abandoned wait into `base + offset`.
+fn wait_on_group(group: &[HANDLE], base: usize, timeout: u32) -> Result<Option<usize>> {
+    let result = unsafe { WaitForMultipleObjects(group, false, timeout) };
+
+    if result == WAIT_TIMEOUT {
+        return Ok(None);
+    }
+
+    let signaled_range = WAIT_OBJECT_0.0..WAIT_OBJECT_0.0 + group.len() as u32;
+    if signaled_range.contains(&result.0) {
+        return Ok(Some(base + (result.0 - WAIT_OBJECT_0.0) as usize));
+    }
+
+    let abandoned_range = WAIT_ABANDONED_0.0..WAIT_ABANDONED_0.0 + group.len() as u32;
+    if abandoned_range.contains(&result.0) {
+        return Ok(Some(base + (result.0 - WAIT_ABANDONED_0.0) as usize));
+    }
+
+    Err(Error::Windows(WindowsError::from_win32()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::build_env_block;
+    use std::collections::HashMap;
+
+    fn decode(block: &[u16]) -> Vec<String> {
+        block
+            .split(|&c| c == 0)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| String::from_utf16(entry).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn build_env_block_sorts_vars_case_insensitively() {
+        let env = HashMap::from([
+            ("beta".to_string(), "2".to_string()),
+            ("Alpha".to_string(), "1".to_string()),
+            ("gamma".to_string(), "3".to_string()),
+        ]);
+
+        let block = build_env_block(&env);
+
+        assert_eq!(decode(&block), vec!["Alpha=1", "beta=2", "gamma=3"]);
+    }
+
+    #[test]
+    fn build_env_block_is_double_null_terminated() {
+        let env = HashMap::from([("KEY".to_string(), "value".to_string())]);
+
+        let block = build_env_block(&env);
+
+        assert_eq!(&block[block.len() - 2..], &[0, 0]);
+    }
+
+    #[test]
+    fn build_env_block_empty_env_is_just_the_terminator() {
+        assert_eq!(build_env_block(&HashMap::new()), vec![0]);
+    }
 }